@@ -1,24 +1,33 @@
 use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
     fs,
+    hash::{Hash, Hasher},
     io::{self, Cursor, Write},
+    num::NonZeroU32,
     path::{Path, PathBuf},
+    str::FromStr,
     sync::{Arc, Mutex},
-    time::Instant,
+    time::{Instant, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, Context, Result};
 use exif::{In as IdfNum, Reader as ExifReader, Tag as ExifTag, Value as ExifValue};
-use image::{self, imageops::FilterType, GenericImageView, ImageFormat};
+use fast_image_resize as fr;
+use image::{self, imageops::FilterType, DynamicImage, GenericImageView, ImageFormat};
 use lazy_static::lazy_static;
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 use rust_embed::RustEmbed;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 use tera::Tera;
 
 const NAME: &str = "galerio";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Name of the manifest file used to cache processing results between runs,
+/// relative to the output directory.
+const MANIFEST_FILENAME: &str = ".galerio-manifest.json";
+
 #[derive(RustEmbed)]
 #[folder = "templates/"]
 struct Templates;
@@ -76,12 +85,362 @@ struct Args {
     /// Skip processing image files
     #[structopt(long)]
     skip_processing: bool,
+
+    /// Output image format: jpeg, webp, avif, or "webp+jpeg" to emit both a
+    /// WebP and a JPEG fallback
+    #[structopt(long = "format", default_value = "jpeg")]
+    format: OutputFormat,
+
+    /// Generate additional full-size width variants (comma-separated, e.g.
+    /// "480,960,1600") alongside the single largest one, instead of just the
+    /// latter. The gallery page itself always links to the largest variant;
+    /// the others are written to the output directory for other tools or
+    /// pages consuming it to pick from. Overrides `--max-large-size` when
+    /// set.
+    #[structopt(long = "widths", use_delimiter = true)]
+    widths: Vec<u32>,
+
+    /// Read capture date, camera model, focal length and GPS position from
+    /// EXIF data and show them as a per-photo caption. Also sorts images by
+    /// capture date instead of by filename.
+    #[structopt(long = "show-metadata")]
+    show_metadata: bool,
+}
+
+/// The image format(s) that processed images should be written as.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum OutputFormat {
+    Jpeg,
+    Webp,
+    Avif,
+    WebpJpeg,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "webp" => Ok(OutputFormat::Webp),
+            "avif" => Ok(OutputFormat::Avif),
+            "webp+jpeg" | "webp+jpg" => Ok(OutputFormat::WebpJpeg),
+            other => Err(anyhow!(
+                "Unknown format {:?}, expected jpeg, webp, avif or webp+jpeg",
+                other
+            )),
+        }
+    }
+}
+
+/// Return the [`ImageFormat`] used for the primary filename of an `Image`
+/// (JPEG for `webp+jpeg`, otherwise the single requested format), e.g. the
+/// one used for `skip_processing` runs where no encoding actually happens.
+fn primary_output_format(format: OutputFormat) -> ImageFormat {
+    match format {
+        OutputFormat::Jpeg | OutputFormat::WebpJpeg => ImageFormat::Jpeg,
+        OutputFormat::Webp => ImageFormat::WebP,
+        OutputFormat::Avif => ImageFormat::Avif,
+    }
+}
+
+/// Return the file extension used for the given image format.
+fn format_extension(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Jpeg => "jpg",
+        ImageFormat::WebP => "webp",
+        ImageFormat::Avif => "avif",
+        _ => "bin",
+    }
+}
+
+/// Whether `ext` is a file extension galerio can read images from. JPEG,
+/// PNG, TIFF and WebP are decoded via the `image` crate; HEIF/HEIC require
+/// the `heif` feature, since decoding them needs the system `libheif`.
+fn is_supported_input_extension(ext: &str) -> bool {
+    match ext.to_lowercase().as_str() {
+        "jpg" | "jpeg" | "png" | "tif" | "tiff" | "webp" => true,
+        #[cfg(feature = "heif")]
+        "heif" | "heic" => true,
+        _ => false,
+    }
+}
+
+/// Whether a source file with extension `source_ext` can be copied into the
+/// gallery unmodified instead of being decoded and re-encoded. This is only
+/// the case when a single output format was requested and the source is
+/// already in that exact format; `webp+jpeg` always needs both files
+/// generated, and any other mismatch needs transcoding to be web-deliverable
+/// in the requested format.
+fn copy_as_is_compatible(source_ext: &str, format: OutputFormat) -> bool {
+    match format {
+        OutputFormat::WebpJpeg => false,
+        single => {
+            let ext = source_ext.to_lowercase();
+            ext == format_extension(primary_output_format(single))
+                || (single == OutputFormat::Jpeg && ext == "jpeg")
+        }
+    }
+}
+
+/// Error out if two input files share the same file stem (e.g. `IMG_0001.jpg`
+/// and `IMG_0001.HEIC` side by side), since output filenames are derived from
+/// the stem alone (`<stem>.thumb.*`, `<stem>.<ext>`, ...). Accepting several
+/// input extensions makes such collisions easy to hit in practice (e.g. a
+/// phone's camera roll export), and without this check the two inputs would
+/// silently overwrite each other's output files depending on processing
+/// order.
+fn check_for_duplicate_stems(files: &[PathBuf]) -> Result<()> {
+    let mut seen_stems: HashMap<String, &PathBuf> = HashMap::new();
+    for file in files {
+        let stem = file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| anyhow!("Could not determine file stem for file {:?}", file))?;
+        if let Some(previous) = seen_stems.insert(stem.to_string(), file) {
+            return Err(anyhow!(
+                "Input files {:?} and {:?} both resolve to the output stem {:?}; rename one of them to avoid their outputs overwriting each other",
+                previous,
+                file,
+                stem
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Decode an input image, dispatching to a HEIF-specific decoder (behind the
+/// `heif` feature) for `.heif`/`.heic` files and to `image`'s own decoders
+/// for everything else.
+fn decode_input_image(image_path: impl AsRef<Path>) -> Result<DynamicImage> {
+    #[cfg(feature = "heif")]
+    {
+        let ext = image_path
+            .as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        if ext == "heif" || ext == "heic" {
+            return decode_heif_image(image_path.as_ref());
+        }
+    }
+    Ok(image::open(image_path)?)
+}
+
+/// Decode a HEIF/HEIC file via `libheif-rs` into a `DynamicImage`.
+#[cfg(feature = "heif")]
+fn decode_heif_image(image_path: &Path) -> Result<DynamicImage> {
+    let ctx = libheif_rs::HeifContext::read_from_file(
+        image_path
+            .to_str()
+            .ok_or_else(|| anyhow!("Non-UTF8 HEIF path: {:?}", image_path))?,
+    )?;
+    let handle = ctx.primary_image_handle()?;
+    let heif_image = handle.decode(
+        libheif_rs::ColorSpace::Rgb(libheif_rs::RgbChroma::Rgb),
+        None,
+    )?;
+    let width = heif_image.width();
+    let height = heif_image.height();
+    let plane = heif_image
+        .planes()
+        .interleaved
+        .ok_or_else(|| anyhow!("HEIF image has no interleaved RGB plane"))?;
+    let buffer = image::RgbImage::from_raw(width, height, plane.data.to_vec())
+        .ok_or_else(|| anyhow!("Failed to build RGB buffer from decoded HEIF data"))?;
+    Ok(DynamicImage::ImageRgb8(buffer))
+}
+
+/// Encode `img` into every [`ImageFormat`] requested by `format`.
+///
+/// Returns one buffer per output format. AVIF encoding requires the `avif`
+/// feature to be enabled.
+fn encode_image(img: &DynamicImage, format: OutputFormat) -> Result<Vec<(ImageFormat, Vec<u8>)>> {
+    let mut outputs = Vec::new();
+
+    if matches!(format, OutputFormat::Jpeg | OutputFormat::WebpJpeg) {
+        let mut buf = Vec::new();
+        img.write_to(&mut Cursor::new(&mut buf), ImageFormat::Jpeg)?;
+        outputs.push((ImageFormat::Jpeg, buf));
+    }
+
+    if matches!(format, OutputFormat::Webp | OutputFormat::WebpJpeg) {
+        let mut buf = Vec::new();
+        img.write_to(&mut Cursor::new(&mut buf), ImageFormat::WebP)?;
+        outputs.push((ImageFormat::WebP, buf));
+    }
+
+    if matches!(format, OutputFormat::Avif) {
+        #[cfg(feature = "avif")]
+        {
+            let mut buf = Vec::new();
+            img.write_to(&mut Cursor::new(&mut buf), ImageFormat::Avif)?;
+            outputs.push((ImageFormat::Avif, buf));
+        }
+        #[cfg(not(feature = "avif"))]
+        {
+            return Err(anyhow!(
+                "AVIF output requires galerio to be built with the `avif` feature"
+            ));
+        }
+    }
+
+    Ok(outputs)
+}
+
+/// Pick the filename used as the non-WebP `<img>` source and, if a WebP
+/// variant was also generated, the filename used as the `<source>` in a
+/// `<picture>` element.
+fn pick_filenames(outputs: &[(ImageFormat, String)]) -> (String, Option<String>) {
+    let webp = outputs
+        .iter()
+        .find(|(format, _)| *format == ImageFormat::WebP)
+        .map(|(_, filename)| filename.clone());
+    let other = outputs
+        .iter()
+        .find(|(format, _)| *format != ImageFormat::WebP)
+        .map(|(_, filename)| filename.clone());
+    match other {
+        Some(filename) => (filename, webp),
+        // Only a WebP variant was generated, use it as the primary source too.
+        None => (webp.expect("encode_image returned no outputs"), None),
+    }
 }
 
 #[derive(Serialize)]
 struct Image {
     filename_full: String,
+    filename_full_webp: Option<String>,
+    filename_thumb: String,
+    filename_thumb_webp: Option<String>,
+    /// Additional full-size width variants written to disk when
+    /// `--widths` is used, so visitors (or other tools consuming the
+    /// output directory) can link to a specific size. Empty otherwise, in
+    /// which case `filename_full` is the only full-size image. Not used
+    /// for the gallery page's own `<img>`, which always links to
+    /// `filename_full` (the largest variant) and renders `thumb_variants`
+    /// below for the grid tile.
+    variants: Vec<ImageVariant>,
+    /// 1x/2x pixel-density variants of the thumbnail, for the gallery grid's
+    /// `srcset`. `filename_thumb`/`filename_thumb_webp` above are the 1x
+    /// entry of this list, kept as separate fields for the `<img src>`
+    /// fallback.
+    thumb_variants: Vec<ThumbVariant>,
+    /// EXIF-derived caption data, populated when `--show-metadata` is used.
+    metadata: Option<PhotoMetadata>,
+}
+
+/// A single responsive width variant of the full-size image.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImageVariant {
+    width: u32,
+    filename: String,
+    filename_webp: Option<String>,
+}
+
+/// A single pixel-density variant of the thumbnail (`1x`, `2x`, ...).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ThumbVariant {
+    density: u32,
+    filename: String,
+    filename_webp: Option<String>,
+}
+
+/// A persisted cache of processing results, so that unchanged inputs can be
+/// skipped on the next run. Stored as [`MANIFEST_FILENAME`] in the output
+/// directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    entries: HashMap<String, ManifestEntry>,
+}
+
+/// A single cached processing result, keyed by the source filename.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManifestEntry {
+    /// Hash of the source file's mtime and size plus every parameter that
+    /// influences its output. If this doesn't match the freshly computed cache key, the
+    /// entry is stale and the image is reprocessed.
+    cache_key: String,
+    filename_full: String,
+    filename_full_webp: Option<String>,
     filename_thumb: String,
+    filename_thumb_webp: Option<String>,
+    #[serde(default)]
+    variants: Vec<ImageVariant>,
+    #[serde(default)]
+    thumb_variants: Vec<ThumbVariant>,
+    #[serde(default)]
+    metadata: Option<PhotoMetadata>,
+}
+
+impl Manifest {
+    fn load(output_dir: &Path) -> Manifest {
+        fs::read_to_string(output_dir.join(MANIFEST_FILENAME))
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn write(&self, output_dir: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(output_dir.join(MANIFEST_FILENAME), json)?;
+        Ok(())
+    }
+}
+
+/// Compute the cache key for a source image: a hash of its mtime and size,
+/// combined with every parameter that affects how it's processed. Changing
+/// any of these parameters (or the galerio version) invalidates the cache
+/// for every image, since none of the recomputed keys will match anymore.
+fn compute_cache_key(
+    source_path: impl AsRef<Path>,
+    thumbnail_height: u32,
+    max_large_size: Option<u32>,
+    resize_include_panorama: bool,
+    format: OutputFormat,
+    widths: &[u32],
+    show_metadata: bool,
+) -> Result<String> {
+    let metadata = fs::metadata(source_path)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut hasher = DefaultHasher::new();
+    VERSION.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    mtime.hash(&mut hasher);
+    thumbnail_height.hash(&mut hasher);
+    max_large_size.hash(&mut hasher);
+    resize_include_panorama.hash(&mut hasher);
+    format.hash(&mut hasher);
+    // Hash a sorted copy: the processing path also sorts `--widths` before
+    // naming/resizing, so an equivalent but differently-ordered list must
+    // produce the same key instead of needlessly invalidating the cache.
+    let mut sorted_widths = widths.to_vec();
+    sorted_widths.sort_unstable();
+    sorted_widths.hash(&mut hasher);
+    show_metadata.hash(&mut hasher);
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// Check whether every output file referenced by a manifest entry still
+/// exists in the output directory.
+fn outputs_exist(output_dir: &Path, entry: &ManifestEntry) -> bool {
+    let exists = |filename: &str| output_dir.join(filename).is_file();
+    exists(&entry.filename_full)
+        && entry.filename_full_webp.as_deref().map_or(true, exists)
+        && exists(&entry.filename_thumb)
+        && entry.filename_thumb_webp.as_deref().map_or(true, exists)
+        && entry.variants.iter().all(|variant| {
+            exists(&variant.filename) && variant.filename_webp.as_deref().map_or(true, exists)
+        })
+        && entry.thumb_variants.iter().all(|variant| {
+            exists(&variant.filename) && variant.filename_webp.as_deref().map_or(true, exists)
+        })
 }
 
 #[derive(Serialize)]
@@ -96,60 +455,210 @@ struct TemplateContext {
 
 /// Get the width and height of the image (whichever
 fn get_dimensions(image_path: impl AsRef<Path>) -> Result<(u32, u32)> {
-    let img = image::open(image_path)?;
+    let img = decode_input_image(image_path)?;
     Ok(img.dimensions())
 }
 
-/// Generate a resized image from the `image_path`, return the resized bytes.
-fn resize_image(
+/// Open `image_path` and apply the EXIF rotation, unless the image is a
+/// panorama and `panorama_detection` is enabled, in which case it's
+/// returned untouched. Returns the decoded image plus whether it should
+/// still be resized by the caller.
+fn decode_and_orient(
     image_path: impl AsRef<Path>,
-    max_width: u32,
-    max_height: u32,
     orientation: &Orientation,
     panorama_detection: bool,
-) -> Result<Vec<u8>> {
-    // Open original image
-    let mut img = image::open(image_path)?;
+) -> Result<(DynamicImage, bool)> {
+    let mut img = decode_input_image(image_path)?;
 
     // Panorama detection: Aspect ratio more than 2:1?
     let (w, h) = img.dimensions();
     let is_panorama = w as f32 / h as f32 > 2.0;
+    let should_resize = !(is_panorama && panorama_detection);
 
-    // For non-panoramas: Apply rotation, then resize
-    if !(is_panorama && panorama_detection) {
-        img = match orientation {
-            Orientation::Deg0 => img,
-            Orientation::Deg90 => img.rotate270(),
-            Orientation::Deg180 => img.rotate180(),
-            Orientation::Deg270 => img.rotate90(),
-        }
-        .resize(max_width, max_height, FilterType::CatmullRom);
+    if should_resize {
+        img = apply_orientation(img, orientation);
     }
 
-    // Write and return buffer
-    let mut buf = Vec::new();
-    img.write_to(&mut Cursor::new(&mut buf), ImageFormat::Jpeg)?;
-    Ok(buf)
+    Ok((img, should_resize))
 }
 
-/// An image orientation.
+/// Apply the rotation/flip combination for a single EXIF orientation to an
+/// image, undoing whatever the camera recorded so the result displays
+/// upright.
+fn apply_orientation(img: DynamicImage, orientation: &Orientation) -> DynamicImage {
+    match orientation {
+        Orientation::Deg0 => img,
+        Orientation::Deg90 => img.rotate270(),
+        Orientation::Deg180 => img.rotate180(),
+        Orientation::Deg270 => img.rotate90(),
+        Orientation::FlipH => img.fliph(),
+        Orientation::FlipV => img.flipv(),
+        Orientation::FlipHDeg90 => img.fliph().rotate270(),
+        Orientation::FlipHDeg270 => img.fliph().rotate90(),
+    }
+}
+
+/// Generate a resized image from the `image_path`, return the resized
+/// (but not yet encoded) image.
+fn resize_image(
+    image_path: impl AsRef<Path>,
+    max_width: u32,
+    max_height: u32,
+    orientation: &Orientation,
+    panorama_detection: bool,
+) -> Result<DynamicImage> {
+    let (img, should_resize) = decode_and_orient(image_path, orientation, panorama_detection)?;
+    if !should_resize {
+        return Ok(img);
+    }
+    Ok(fast_resize(&img, max_width, max_height)
+        .unwrap_or_else(|| img.resize(max_width, max_height, FilterType::CatmullRom)))
+}
+
+/// Generate one resized image per entry in `widths`, decoding the source
+/// image only once and reusing it for every width. Returns `(width,
+/// image)` pairs; requested widths larger than the source are clamped down
+/// to it, and clamped widths are deduplicated so that e.g. requesting
+/// `960,1600` against an 800px-wide source doesn't produce two identical
+/// `800px` variants. `widths` must already be sorted. If the image is a
+/// skipped panorama, a single unresized variant is returned instead.
+fn resize_to_widths(
+    image_path: impl AsRef<Path>,
+    widths: &[u32],
+    orientation: &Orientation,
+    panorama_detection: bool,
+) -> Result<Vec<(u32, DynamicImage)>> {
+    let (img, should_resize) = decode_and_orient(image_path, orientation, panorama_detection)?;
+    let (orig_width, _) = img.dimensions();
+
+    if !should_resize {
+        return Ok(vec![(orig_width, img)]);
+    }
+
+    let target_widths = clamp_and_dedup_widths(widths, orig_width);
+
+    target_widths
+        .into_iter()
+        .map(|target_width| {
+            let resized = fast_resize(&img, target_width, u32::MAX)
+                .unwrap_or_else(|| img.resize(target_width, u32::MAX, FilterType::CatmullRom));
+            Ok((target_width, resized))
+        })
+        .collect()
+}
+
+/// Clamp each of `widths` (assumed sorted ascending) down to `orig_width`
+/// and deduplicate, so that multiple requested widths exceeding the source
+/// width collapse into a single variant instead of duplicate same-size
+/// outputs.
+fn clamp_and_dedup_widths(widths: &[u32], orig_width: u32) -> Vec<u32> {
+    let mut target_widths: Vec<u32> = widths.iter().map(|&width| width.min(orig_width)).collect();
+    target_widths.dedup();
+    target_widths
+}
+
+/// Compute the box-fit target dimensions for a resize, preserving aspect
+/// ratio the same way `image::DynamicImage::resize` does.
+fn box_fit_dimensions(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    let wratio = max_width as f64 / width as f64;
+    let hratio = max_height as f64 / height as f64;
+    let ratio = wratio.min(hratio);
+    let nwidth = (width as f64 * ratio).round().max(1.0) as u32;
+    let nheight = (height as f64 * ratio).round().max(1.0) as u32;
+    (nwidth, nheight)
+}
+
+/// Resize `img` using `fast_image_resize`'s SIMD-accelerated convolution,
+/// which is dramatically faster than `image`'s scalar resize on large
+/// batches. Returns `None` for pixel formats it doesn't handle, so the
+/// caller can fall back to `image`'s resize.
+fn fast_resize(img: &DynamicImage, max_width: u32, max_height: u32) -> Option<DynamicImage> {
+    let (width, height) = img.dimensions();
+    let (new_width, new_height) = box_fit_dimensions(width, height, max_width, max_height);
+
+    let src_width = NonZeroU32::new(width)?;
+    let src_height = NonZeroU32::new(height)?;
+    let dst_width = NonZeroU32::new(new_width)?;
+    let dst_height = NonZeroU32::new(new_height)?;
+
+    let (pixel_type, data) = match img {
+        DynamicImage::ImageRgb8(buf) => (fr::PixelType::U8x3, buf.as_raw().clone()),
+        DynamicImage::ImageRgba8(buf) => (fr::PixelType::U8x4, buf.as_raw().clone()),
+        // Other pixel formats (luma, 16-bit, ...) aren't worth special-casing
+        // here; fall back to the `image`-based path for those.
+        _ => return None,
+    };
+
+    let src_image = fr::Image::from_vec_u8(src_width, src_height, data, pixel_type).ok()?;
+    let mut dst_image = fr::Image::new(dst_width, dst_height, pixel_type);
+
+    let mut resizer = fr::Resizer::new(fr::ResizeAlg::Convolution(fr::FilterType::CatmullRom));
+    resizer
+        .resize(&src_image.view(), &mut dst_image.view_mut())
+        .ok()?;
+
+    let buffer = dst_image.into_vec();
+    match pixel_type {
+        fr::PixelType::U8x3 => {
+            image::RgbImage::from_raw(new_width, new_height, buffer).map(DynamicImage::ImageRgb8)
+        }
+        fr::PixelType::U8x4 => image::RgbaImage::from_raw(new_width, new_height, buffer)
+            .map(DynamicImage::ImageRgba8),
+        _ => None,
+    }
+}
+
+/// An image orientation, covering all eight EXIF orientation states (plain
+/// rotations as well as the four mirrored ones).
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum Orientation {
     Deg0,
     Deg90,
     Deg180,
     Deg270,
+    FlipH,
+    FlipV,
+    FlipHDeg90,
+    FlipHDeg270,
 }
 
-/// Read the orientation from the EXIF data.
-///
-/// In contrast to the full EXIF format, this only supports rotation, no
-/// mirroring. If something goes wrong or if the image is mirrored,
-/// `Orientation::Deg0` will be returned.
-fn get_orientation(image_path: impl AsRef<Path>) -> Result<Orientation> {
+/// Map a raw EXIF `Orientation` tag value (1-8) to an [`Orientation`].
+/// Unknown values are treated as the identity orientation.
+fn orientation_from_exif_value(value: u16) -> Orientation {
+    match value {
+        1 => Orientation::Deg0,
+        2 => Orientation::FlipH,
+        3 => Orientation::Deg180,
+        4 => Orientation::FlipV,
+        5 => Orientation::FlipHDeg90,
+        6 => Orientation::Deg270,
+        7 => Orientation::FlipHDeg270,
+        8 => Orientation::Deg90,
+        _ => Orientation::Deg0,
+    }
+}
+
+/// Photo caption data surfaced when `--show-metadata` is passed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PhotoMetadata {
+    capture_date: Option<String>,
+    camera_model: Option<String>,
+    focal_length: Option<String>,
+    gps: Option<(f64, f64)>,
+}
+
+/// Read the orientation from the EXIF data and, if `with_metadata` is set,
+/// a handful of additional EXIF fields used for photo captions. Since the
+/// file is already opened and parsed for the orientation tag, reading a
+/// few more fields from the same `exif::Exif` adds negligible I/O.
+fn read_exif_data(
+    image_path: impl AsRef<Path>,
+    with_metadata: bool,
+) -> Result<(Orientation, Option<PhotoMetadata>)> {
     let file = fs::File::open(&image_path)?;
-    let orientation = ExifReader::new()
-        .read_from_container(&mut std::io::BufReader::new(&file))?
+    let exif = ExifReader::new().read_from_container(&mut std::io::BufReader::new(&file))?;
+
+    let orientation = exif
         .get_field(ExifTag::Orientation, IdfNum::PRIMARY)
         .map(|field| field.value.clone())
         .and_then(|val: ExifValue| {
@@ -159,14 +668,87 @@ fn get_orientation(image_path: impl AsRef<Path>) -> Result<Orientation> {
                 None
             }
         })
-        .map(|orientation| match orientation {
-            1 => Orientation::Deg0,
-            8 => Orientation::Deg90,
-            3 => Orientation::Deg180,
-            6 => Orientation::Deg270,
-            _ => Orientation::Deg0,
-        });
-    Ok(orientation.unwrap_or(Orientation::Deg0))
+        .map(orientation_from_exif_value)
+        .unwrap_or(Orientation::Deg0);
+
+    let metadata = if with_metadata {
+        Some(PhotoMetadata {
+            capture_date: exif_ascii(&exif, ExifTag::DateTimeOriginal)
+                .as_deref()
+                .and_then(format_exif_datetime),
+            camera_model: exif_ascii(&exif, ExifTag::Model),
+            focal_length: exif_rational(&exif, ExifTag::FocalLength)
+                .map(|mm| format!("{:.0} mm", mm)),
+            gps: match (
+                exif_gps_coord(&exif, ExifTag::GPSLatitude, ExifTag::GPSLatitudeRef),
+                exif_gps_coord(&exif, ExifTag::GPSLongitude, ExifTag::GPSLongitudeRef),
+            ) {
+                (Some(lat), Some(lon)) => Some((lat, lon)),
+                _ => None,
+            },
+        })
+    } else {
+        None
+    };
+
+    Ok((orientation, metadata))
+}
+
+/// Read an ASCII EXIF field as a `String`, with trailing NUL bytes removed.
+fn exif_ascii(exif: &exif::Exif, tag: ExifTag) -> Option<String> {
+    exif.get_field(tag, IdfNum::PRIMARY).and_then(|field| {
+        if let ExifValue::Ascii(ref strings) = field.value {
+            strings
+                .first()
+                .map(|bytes| String::from_utf8_lossy(bytes).trim_end_matches('\0').into())
+        } else {
+            None
+        }
+    })
+}
+
+/// Read a single-value rational EXIF field as an `f64`.
+fn exif_rational(exif: &exif::Exif, tag: ExifTag) -> Option<f64> {
+    exif.get_field(tag, IdfNum::PRIMARY).and_then(|field| {
+        if let ExifValue::Rational(ref rationals) = field.value {
+            rationals.first().map(|r| r.to_f64())
+        } else {
+            None
+        }
+    })
+}
+
+/// Read a GPS coordinate (degrees/minutes/seconds plus hemisphere
+/// reference) as signed decimal degrees.
+fn exif_gps_coord(exif: &exif::Exif, value_tag: ExifTag, ref_tag: ExifTag) -> Option<f64> {
+    let field = exif.get_field(value_tag, IdfNum::PRIMARY)?;
+    let rationals = match &field.value {
+        ExifValue::Rational(rationals) if rationals.len() == 3 => rationals,
+        _ => return None,
+    };
+    let degrees =
+        rationals[0].to_f64() + rationals[1].to_f64() / 60.0 + rationals[2].to_f64() / 3600.0;
+
+    let is_negative = exif
+        .get_field(ref_tag, IdfNum::PRIMARY)
+        .and_then(|field| {
+            if let ExifValue::Ascii(ref strings) = field.value {
+                strings.first().and_then(|bytes| bytes.first().copied())
+            } else {
+                None
+            }
+        })
+        .map(|letter| letter == b'S' || letter == b'W')
+        .unwrap_or(false);
+
+    Some(if is_negative { -degrees } else { degrees })
+}
+
+/// Reformat an EXIF `"YYYY:MM:DD HH:MM:SS"` timestamp into the more
+/// conventional `"YYYY-MM-DD HH:MM:SS"`, without touching the time part.
+fn format_exif_datetime(raw: &str) -> Option<String> {
+    let (date_part, time_part) = raw.split_once(' ')?;
+    Some(format!("{} {}", date_part.replace(':', "-"), time_part))
 }
 
 fn main() -> Result<()> {
@@ -202,14 +784,16 @@ fn main() -> Result<()> {
         })
         .filter(|dir_entry| {
             dir_entry
-                .file_name()
-                .to_str()
-                .map(|s| s.ends_with(".jpg") || s.ends_with(".JPG"))
+                .path()
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(is_supported_input_extension)
                 .unwrap_or(false)
         })
         .map(|dir_entry| dir_entry.path())
         .collect::<Vec<_>>();
     image_files.sort();
+    check_for_duplicate_stems(&image_files)?;
 
     // Determine download ZIP filename
     let download_filename = if args.no_download {
@@ -224,6 +808,10 @@ fn main() -> Result<()> {
         Some(format!("{}.zip", name))
     };
 
+    // Load the manifest from the previous run, if any, so unchanged inputs
+    // can be skipped below.
+    let old_manifest = Manifest::load(&args.output_dir);
+
     // Process images
     let zipfile = Arc::new(Mutex::new(
         download_filename
@@ -232,78 +820,319 @@ fn main() -> Result<()> {
             .map(zip::ZipWriter::new),
     ));
 
-    let images = image_files
+    let mut results = image_files
         .par_iter()
         .map(|f| {
-            // Determine filenames
-            let filename_full = f.file_name().unwrap().to_str().unwrap().to_string();
-            let filename_thumb = format!(
-                "{}.thumb.jpg",
-                f.file_stem()
-                    .and_then(|stem| stem.to_str())
-                    .ok_or_else(|| anyhow!("Could not determine file stem for file {:?}", f))
-                    .unwrap(),
-            );
-
-            // Resize
-            if !args.skip_processing {
-                log!("Processing {:?}", filename_full);
+            let filename_full_orig = f.file_name().unwrap().to_str().unwrap().to_string();
+            let stem = f
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| anyhow!("Could not determine file stem for file {:?}", f))?
+                .to_string();
 
-                // Read orientation from EXIF data
-                let orientation = get_orientation(f).unwrap_or(Orientation::Deg0);
-
-                // Generate and write thumbnail
-                let thumbnail_bytes = resize_image(
+            // Resize, reusing cached outputs from the manifest if the
+            // source file and all relevant parameters are unchanged.
+            let (
+                filename_full,
+                filename_full_webp,
+                filename_thumb,
+                filename_thumb_webp,
+                variants,
+                thumb_variants,
+                metadata,
+                manifest_entry,
+            ) = if !args.skip_processing {
+                let cache_key = compute_cache_key(
                     f,
-                    args.thumbnail_height * 4,
                     args.thumbnail_height,
-                    &orientation,
-                    false,
+                    args.max_large_size,
+                    args.resize_include_panorama,
+                    args.format,
+                    &args.widths,
+                    args.show_metadata,
                 )?;
-                let thumbnail_path = args.output_dir.join(&filename_thumb);
-                fs::write(thumbnail_path, thumbnail_bytes)?;
-
-                // Copy original size file
-                let full_path = args.output_dir.join(&filename_full);
-                if let Some(max_size) = args.max_large_size {
-                    let (w, h) = get_dimensions(f)?;
-                    if w > max_size || h > max_size {
-                        // Resize large image
-                        let large_bytes = resize_image(
+                let cached = old_manifest
+                    .entries
+                    .get(&filename_full_orig)
+                    .filter(|entry| {
+                        entry.cache_key == cache_key && outputs_exist(&args.output_dir, entry)
+                    });
+
+                if let Some(entry) = cached {
+                    log!("Skipping {:?} (unchanged)", filename_full_orig);
+                    (
+                        entry.filename_full.clone(),
+                        entry.filename_full_webp.clone(),
+                        entry.filename_thumb.clone(),
+                        entry.filename_thumb_webp.clone(),
+                        entry.variants.clone(),
+                        entry.thumb_variants.clone(),
+                        entry.metadata.clone(),
+                        Some(entry.clone()),
+                    )
+                } else {
+                    log!("Processing {:?}", filename_full_orig);
+
+                    // Read orientation (and, if requested, caption
+                    // metadata) from EXIF data
+                    let (orientation, metadata) =
+                        read_exif_data(f, args.show_metadata).unwrap_or((Orientation::Deg0, None));
+
+                    // Generate and write thumbnail
+                    let thumbnail = resize_image(
+                        f,
+                        args.thumbnail_height * 4,
+                        args.thumbnail_height,
+                        &orientation,
+                        false,
+                    )?;
+                    let thumbnail_outputs: Vec<(ImageFormat, String)> =
+                        encode_image(&thumbnail, args.format)?
+                            .into_iter()
+                            .map(|(format, bytes)| {
+                                let filename =
+                                    format!("{}.thumb.{}", stem, format_extension(format));
+                                fs::write(args.output_dir.join(&filename), bytes)?;
+                                Ok((format, filename))
+                            })
+                            .collect::<Result<Vec<_>>>()?;
+                    let (filename_thumb, filename_thumb_webp) = pick_filenames(&thumbnail_outputs);
+
+                    // Also generate a 2x-density thumbnail for retina
+                    // screens, so the gallery grid's `<img srcset>` can pick
+                    // an appropriately thumbnail-scale image instead of
+                    // reusing the full-size `--widths` variants (which are
+                    // much larger than anything the ~300px-tall grid tile
+                    // ever needs).
+                    let thumbnail_2x = resize_image(
+                        f,
+                        args.thumbnail_height * 8,
+                        args.thumbnail_height * 2,
+                        &orientation,
+                        false,
+                    )?;
+                    let thumbnail_2x_outputs: Vec<(ImageFormat, String)> =
+                        encode_image(&thumbnail_2x, args.format)?
+                            .into_iter()
+                            .map(|(format, bytes)| {
+                                let filename =
+                                    format!("{}.thumb@2x.{}", stem, format_extension(format));
+                                fs::write(args.output_dir.join(&filename), bytes)?;
+                                Ok((format, filename))
+                            })
+                            .collect::<Result<Vec<_>>>()?;
+                    let (filename_thumb_2x, filename_thumb_webp_2x) =
+                        pick_filenames(&thumbnail_2x_outputs);
+                    let thumb_variants = vec![
+                        ThumbVariant {
+                            density: 1,
+                            filename: filename_thumb.clone(),
+                            filename_webp: filename_thumb_webp.clone(),
+                        },
+                        ThumbVariant {
+                            density: 2,
+                            filename: filename_thumb_2x,
+                            filename_webp: filename_thumb_webp_2x,
+                        },
+                    ];
+
+                    // Full-size image(s). If `--widths` was given, emit one
+                    // variant per requested width, with the largest feeding
+                    // the ZIP. Otherwise fall back to the single-image
+                    // behavior: resize (and re-encode) if it exceeds
+                    // `max_large_size`, re-encode if the source isn't in the
+                    // requested output format, otherwise copy as-is.
+                    let (filename_full, filename_full_webp, variants) = if !args.widths.is_empty()
+                    {
+                        let mut sorted_widths = args.widths.clone();
+                        sorted_widths.sort_unstable();
+
+                        let resized = resize_to_widths(
                             f,
-                            max_size,
-                            max_size,
+                            &sorted_widths,
                             &orientation,
                             !args.resize_include_panorama,
                         )?;
-                        fs::write(&full_path, large_bytes)?;
+                        let variants = resized
+                            .into_iter()
+                            .map(|(width, image)| {
+                                let outputs: Vec<(ImageFormat, String)> =
+                                    encode_image(&image, args.format)?
+                                        .into_iter()
+                                        .map(|(format, bytes)| {
+                                            let filename = format!(
+                                                "{}.w{}.{}",
+                                                stem,
+                                                width,
+                                                format_extension(format)
+                                            );
+                                            fs::write(args.output_dir.join(&filename), bytes)?;
+                                            Ok((format, filename))
+                                        })
+                                        .collect::<Result<Vec<_>>>()?;
+                                let (filename, filename_webp) = pick_filenames(&outputs);
+                                Ok(ImageVariant {
+                                    width,
+                                    filename,
+                                    filename_webp,
+                                })
+                            })
+                            .collect::<Result<Vec<_>>>()?;
+
+                        // The largest variant doubles as the "full" image,
+                        // used for the gallery link and the ZIP download.
+                        let largest = variants
+                            .last()
+                            .ok_or_else(|| anyhow!("--widths produced no variants"))?;
+                        let filename_full = largest.filename.clone();
+                        let filename_full_webp = largest.filename_webp.clone();
+
+                        (filename_full, filename_full_webp, variants)
                     } else {
-                        // Image is smaller than max size, copy as-is
-                        fs::copy(f, &full_path)?;
-                    }
-                } else {
-                    // No max-large-size parameter specified, copy original
-                    fs::copy(f, &full_path)?;
+                        let needs_resize = match args.max_large_size {
+                            Some(max_size) => {
+                                let (w, h) = get_dimensions(f)?;
+                                w > max_size || h > max_size
+                            }
+                            None => false,
+                        };
+                        let source_ext =
+                            f.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+                        let needs_transcode =
+                            !needs_resize && !copy_as_is_compatible(source_ext, args.format);
+
+                        let (filename_full, filename_full_webp) = if needs_resize
+                            || needs_transcode
+                        {
+                            let large = if needs_resize {
+                                let max_size = args.max_large_size.unwrap();
+                                resize_image(
+                                    f,
+                                    max_size,
+                                    max_size,
+                                    &orientation,
+                                    !args.resize_include_panorama,
+                                )?
+                            } else {
+                                // Under the max size, but not in a format
+                                // that matches the requested output, so
+                                // decode and re-encode instead of copying.
+                                decode_and_orient(f, &orientation, !args.resize_include_panorama)?
+                                    .0
+                            };
+                            let full_outputs: Vec<(ImageFormat, String)> =
+                                encode_image(&large, args.format)?
+                                    .into_iter()
+                                    .map(|(format, bytes)| {
+                                        let filename =
+                                            format!("{}.{}", stem, format_extension(format));
+                                        fs::write(args.output_dir.join(&filename), bytes)?;
+                                        Ok((format, filename))
+                                    })
+                                    .collect::<Result<Vec<_>>>()?;
+                            pick_filenames(&full_outputs)
+                        } else {
+                            // Already in the requested output format and
+                            // under the max size (or no max size was
+                            // given), copy as-is.
+                            let full_path = args.output_dir.join(&filename_full_orig);
+                            fs::copy(f, &full_path)?;
+                            (filename_full_orig.clone(), None)
+                        };
+                        (filename_full, filename_full_webp, Vec::new())
+                    };
+
+                    let entry = ManifestEntry {
+                        cache_key,
+                        filename_full: filename_full.clone(),
+                        filename_full_webp: filename_full_webp.clone(),
+                        filename_thumb: filename_thumb.clone(),
+                        filename_thumb_webp: filename_thumb_webp.clone(),
+                        variants: variants.clone(),
+                        thumb_variants: thumb_variants.clone(),
+                        metadata: metadata.clone(),
+                    };
+                    (
+                        filename_full,
+                        filename_full_webp,
+                        filename_thumb,
+                        filename_thumb_webp,
+                        variants,
+                        thumb_variants,
+                        metadata,
+                        Some(entry),
+                    )
                 }
+            } else {
+                let ext = format_extension(primary_output_format(args.format));
+                (
+                    filename_full_orig.clone(),
+                    None,
+                    format!("{}.thumb.{}", stem, ext),
+                    None,
+                    Vec::new(),
+                    Vec::new(),
+                    None,
+                    None,
+                )
+            };
 
-                // Add file to ZIP
+            // Add full-size file to ZIP. The ZIP is rebuilt on every run
+            // even if the underlying image was skipped above.
+            if !args.skip_processing {
                 let options = zip::write::FileOptions::default()
                     .compression_method(zip::CompressionMethod::Stored);
-
                 if let Some(zipwriter) = zipfile.lock().expect("Couldn't lock zipfile").as_mut() {
+                    let full_path = args.output_dir.join(&filename_full);
                     zipwriter.start_file(&filename_full, options).unwrap();
                     zipwriter.write_all(&fs::read(&full_path).unwrap()).unwrap();
                 }
             }
 
             // Store
-            Ok(Image {
-                filename_full,
-                filename_thumb,
-            })
+            Ok((
+                Image {
+                    filename_full,
+                    filename_full_webp,
+                    filename_thumb,
+                    filename_thumb_webp,
+                    variants,
+                    thumb_variants,
+                    metadata,
+                },
+                filename_full_orig,
+                manifest_entry,
+            ))
         })
         .collect::<Result<Vec<_>>>()?;
 
+    // Persist the manifest for the next run. Images that were skipped via
+    // `--skip-processing` don't carry fresh cache information, so their
+    // previous manifest entry (if any) is kept as-is.
+    let mut new_manifest = Manifest::default();
+    for (_, filename_full_orig, manifest_entry) in &results {
+        let entry = manifest_entry
+            .clone()
+            .or_else(|| old_manifest.entries.get(filename_full_orig).cloned());
+        if let Some(entry) = entry {
+            new_manifest.entries.insert(filename_full_orig.clone(), entry);
+        }
+    }
+    new_manifest.write(&args.output_dir)?;
+
+    if args.show_metadata {
+        // Sort by capture date instead of the filename order established
+        // above. Images without a capture date sort to the end.
+        let capture_date = |image: &Image| image.metadata.as_ref().and_then(|m| m.capture_date.clone());
+        results.sort_by(|(a, ..), (b, ..)| match (capture_date(a), capture_date(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        });
+    }
+    let images = results.into_iter().map(|(image, _, _)| image).collect::<Vec<_>>();
+
     let download_filesize_mib = download_filename
         .as_ref()
         .map(|filename| fs::metadata(args.output_dir.join(filename)).unwrap().len())
@@ -355,3 +1184,262 @@ fn main() -> Result<()> {
     log!("Done!");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_filenames_prefers_non_webp_as_primary() {
+        let outputs = vec![
+            (ImageFormat::WebP, "foo.webp".to_string()),
+            (ImageFormat::Jpeg, "foo.jpg".to_string()),
+        ];
+        let (primary, webp) = pick_filenames(&outputs);
+        assert_eq!(primary, "foo.jpg");
+        assert_eq!(webp, Some("foo.webp".to_string()));
+    }
+
+    #[test]
+    fn pick_filenames_single_format_has_no_webp_source() {
+        let outputs = vec![(ImageFormat::Jpeg, "foo.jpg".to_string())];
+        let (primary, webp) = pick_filenames(&outputs);
+        assert_eq!(primary, "foo.jpg");
+        assert_eq!(webp, None);
+    }
+
+    #[test]
+    fn pick_filenames_webp_only_uses_it_as_primary() {
+        let outputs = vec![(ImageFormat::WebP, "foo.webp".to_string())];
+        let (primary, webp) = pick_filenames(&outputs);
+        assert_eq!(primary, "foo.webp");
+        assert_eq!(webp, None);
+    }
+
+    /// Write a throwaway source file under the OS temp dir and return its
+    /// path, so `compute_cache_key` has something to `fs::metadata` against.
+    fn temp_source_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, b"fake image bytes").unwrap();
+        path
+    }
+
+    #[test]
+    fn compute_cache_key_ignores_widths_order() {
+        let path = temp_source_file("galerio_test_cache_key_widths_order.bin");
+        let key_a =
+            compute_cache_key(&path, 200, Some(1600), false, OutputFormat::Jpeg, &[480, 960], false)
+                .unwrap();
+        let key_b =
+            compute_cache_key(&path, 200, Some(1600), false, OutputFormat::Jpeg, &[960, 480], false)
+                .unwrap();
+        assert_eq!(key_a, key_b);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compute_cache_key_changes_with_widths_content() {
+        let path = temp_source_file("galerio_test_cache_key_widths_content.bin");
+        let key_a =
+            compute_cache_key(&path, 200, Some(1600), false, OutputFormat::Jpeg, &[480, 960], false)
+                .unwrap();
+        let key_b =
+            compute_cache_key(&path, 200, Some(1600), false, OutputFormat::Jpeg, &[480, 1600], false)
+                .unwrap();
+        assert_ne!(key_a, key_b);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn outputs_exist_false_when_a_variant_file_is_missing() {
+        let output_dir = std::env::temp_dir().join("galerio_test_outputs_exist");
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(output_dir.join("a.jpg"), b"full").unwrap();
+        fs::write(output_dir.join("a.thumb.jpg"), b"thumb").unwrap();
+
+        let entry = ManifestEntry {
+            cache_key: "irrelevant".to_string(),
+            filename_full: "a.jpg".to_string(),
+            filename_full_webp: None,
+            filename_thumb: "a.thumb.jpg".to_string(),
+            filename_thumb_webp: None,
+            variants: vec![ImageVariant {
+                width: 960,
+                filename: "a.w960.jpg".to_string(),
+                filename_webp: None,
+            }],
+            thumb_variants: Vec::new(),
+            metadata: None,
+        };
+        // The referenced width variant was never written to disk.
+        assert!(!outputs_exist(&output_dir, &entry));
+
+        fs::write(output_dir.join("a.w960.jpg"), b"variant").unwrap();
+        assert!(outputs_exist(&output_dir, &entry));
+
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn box_fit_dimensions_shrinks_landscape_to_width() {
+        // 4000x3000 fit into a 1600x1600 box: width is the binding
+        // constraint, height follows the same ratio.
+        assert_eq!(box_fit_dimensions(4000, 3000, 1600, 1600), (1600, 1200));
+    }
+
+    #[test]
+    fn box_fit_dimensions_shrinks_portrait_to_height() {
+        // 3000x4000 fit into a 1600x1600 box: height is the binding
+        // constraint this time.
+        assert_eq!(box_fit_dimensions(3000, 4000, 1600, 1600), (1200, 1600));
+    }
+
+    #[test]
+    fn box_fit_dimensions_preserves_aspect_ratio_exactly() {
+        let (width, height) = box_fit_dimensions(1920, 1080, 960, 960);
+        assert_eq!(width, 960);
+        assert_eq!(height, 540);
+    }
+
+    #[test]
+    fn clamp_and_dedup_widths_passes_through_widths_under_source() {
+        assert_eq!(clamp_and_dedup_widths(&[480, 960], 1600), vec![480, 960]);
+    }
+
+    #[test]
+    fn clamp_and_dedup_widths_collapses_widths_above_source() {
+        // Both 960 and 1600 exceed an 800px-wide source, so they'd
+        // otherwise clamp to the same value and produce duplicate variants.
+        assert_eq!(clamp_and_dedup_widths(&[480, 960, 1600], 800), vec![480, 800]);
+    }
+
+    #[test]
+    fn orientation_from_exif_value_covers_all_eight_states() {
+        assert_eq!(orientation_from_exif_value(1), Orientation::Deg0);
+        assert_eq!(orientation_from_exif_value(2), Orientation::FlipH);
+        assert_eq!(orientation_from_exif_value(3), Orientation::Deg180);
+        assert_eq!(orientation_from_exif_value(4), Orientation::FlipV);
+        assert_eq!(orientation_from_exif_value(5), Orientation::FlipHDeg90);
+        assert_eq!(orientation_from_exif_value(6), Orientation::Deg270);
+        assert_eq!(orientation_from_exif_value(7), Orientation::FlipHDeg270);
+        assert_eq!(orientation_from_exif_value(8), Orientation::Deg90);
+    }
+
+    #[test]
+    fn orientation_from_exif_value_unknown_is_identity() {
+        assert_eq!(orientation_from_exif_value(0), Orientation::Deg0);
+        assert_eq!(orientation_from_exif_value(9), Orientation::Deg0);
+    }
+
+    /// A 2x2 image with a distinct color in every corner, so flips/rotations
+    /// can be verified pixel-by-pixel instead of just by dimensions.
+    fn corner_marked_image() -> DynamicImage {
+        let buf = image::RgbImage::from_raw(
+            2,
+            2,
+            vec![
+                255, 0, 0, // (0,0) red
+                0, 255, 0, // (1,0) green
+                0, 0, 255, // (0,1) blue
+                255, 255, 0, // (1,1) yellow
+            ],
+        )
+        .unwrap();
+        DynamicImage::ImageRgb8(buf)
+    }
+
+    #[test]
+    fn apply_orientation_deg0_is_identity() {
+        let img = apply_orientation(corner_marked_image(), &Orientation::Deg0);
+        assert_eq!(img.get_pixel(0, 0), corner_marked_image().get_pixel(0, 0));
+        assert_eq!(img.get_pixel(1, 1), corner_marked_image().get_pixel(1, 1));
+    }
+
+    #[test]
+    fn apply_orientation_fliph_mirrors_left_right() {
+        let img = apply_orientation(corner_marked_image(), &Orientation::FlipH);
+        // The red top-left corner moves to the top-right.
+        assert_eq!(img.get_pixel(1, 0), corner_marked_image().get_pixel(0, 0));
+        assert_eq!(img.get_pixel(0, 0), corner_marked_image().get_pixel(1, 0));
+    }
+
+    #[test]
+    fn apply_orientation_flipv_mirrors_top_bottom() {
+        let img = apply_orientation(corner_marked_image(), &Orientation::FlipV);
+        // The red top-left corner moves to the bottom-left.
+        assert_eq!(img.get_pixel(0, 1), corner_marked_image().get_pixel(0, 0));
+        assert_eq!(img.get_pixel(0, 0), corner_marked_image().get_pixel(0, 1));
+    }
+
+    #[test]
+    fn apply_orientation_deg180_is_fliph_and_flipv_combined() {
+        let img = apply_orientation(corner_marked_image(), &Orientation::Deg180);
+        assert_eq!(img.get_pixel(1, 1), corner_marked_image().get_pixel(0, 0));
+        assert_eq!(img.get_pixel(0, 0), corner_marked_image().get_pixel(1, 1));
+    }
+
+    #[test]
+    fn apply_orientation_rotations_swap_dimensions_on_non_square_image() {
+        let img = DynamicImage::ImageRgb8(
+            image::RgbImage::from_raw(4, 2, vec![0u8; 4 * 2 * 3]).unwrap(),
+        );
+        for orientation in [
+            Orientation::Deg90,
+            Orientation::Deg270,
+            Orientation::FlipHDeg90,
+            Orientation::FlipHDeg270,
+        ] {
+            let rotated = apply_orientation(img.clone(), &orientation);
+            assert_eq!(rotated.dimensions(), (2, 4), "{:?}", orientation);
+        }
+        for orientation in [
+            Orientation::Deg0,
+            Orientation::Deg180,
+            Orientation::FlipH,
+            Orientation::FlipV,
+        ] {
+            let unrotated = apply_orientation(img.clone(), &orientation);
+            assert_eq!(unrotated.dimensions(), (4, 2), "{:?}", orientation);
+        }
+    }
+
+    #[test]
+    fn copy_as_is_compatible_matches_single_format() {
+        assert!(copy_as_is_compatible("jpg", OutputFormat::Jpeg));
+        assert!(copy_as_is_compatible("jpeg", OutputFormat::Jpeg));
+        assert!(copy_as_is_compatible("webp", OutputFormat::Webp));
+        assert!(copy_as_is_compatible("avif", OutputFormat::Avif));
+    }
+
+    #[test]
+    fn copy_as_is_compatible_rejects_format_mismatch() {
+        assert!(!copy_as_is_compatible("png", OutputFormat::Jpeg));
+        assert!(!copy_as_is_compatible("webp", OutputFormat::Jpeg));
+        assert!(!copy_as_is_compatible("jpg", OutputFormat::Webp));
+    }
+
+    #[test]
+    fn copy_as_is_compatible_always_false_for_webp_jpeg_dual_output() {
+        // Dual-format output always needs both files (re-)generated.
+        assert!(!copy_as_is_compatible("jpg", OutputFormat::WebpJpeg));
+        assert!(!copy_as_is_compatible("webp", OutputFormat::WebpJpeg));
+    }
+
+    #[test]
+    fn check_for_duplicate_stems_passes_with_distinct_stems() {
+        let files = vec![
+            PathBuf::from("/input/IMG_0001.jpg"),
+            PathBuf::from("/input/IMG_0002.heic"),
+        ];
+        assert!(check_for_duplicate_stems(&files).is_ok());
+    }
+
+    #[test]
+    fn check_for_duplicate_stems_rejects_same_stem_across_extensions() {
+        let files = vec![
+            PathBuf::from("/input/IMG_0001.jpg"),
+            PathBuf::from("/input/IMG_0001.heic"),
+        ];
+        assert!(check_for_duplicate_stems(&files).is_err());
+    }
+}